@@ -1,12 +1,14 @@
+//! A library for reading and writing wave audio files.
+
 extern crate byteorder;
 
 use std::error;
 use std::fmt;
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 use std::result;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 // MARK: Error types
 
@@ -42,6 +44,18 @@ pub enum FormatErrorKind {
     NotAnUncompressedPcmWaveFile(u16),
     /// This file is missing header data and can't be parsed.
     FmtChunkTooShort,
+    /// The "data" subchunk ran out of bytes before a full sample or frame could be read.
+    NotEnoughData,
+    /// This is an RF64/BW64 file but it is missing the mandatory "ds64" chunk, or a chunk's
+    /// size couldn't be resolved from it.
+    MissingDs64Chunk,
+    /// A Microsoft ADPCM block is malformed, e.g. it references a coefficient pair that
+    /// isn't in the "fmt " chunk's coefficient table, or it ran out of bytes mid-nibble.
+    InvalidAdpcmBlock,
+    /// The "bext" chunk is too short to hold the fields Broadcast Wave Format requires.
+    BextChunkTooShort,
+    /// The "fmt " chunk declares zero channels, which can't be divided into frames or samples.
+    InvalidChannelCount,
 }
 
 impl FormatErrorKind {
@@ -51,6 +65,11 @@ impl FormatErrorKind {
             FormatErrorKind::NotAWaveFile => "not a WAVE file",
             FormatErrorKind::NotAnUncompressedPcmWaveFile(_) => "Not an uncompressed wave file",
             FormatErrorKind::FmtChunkTooShort => "fmt_ chunk is too short",
+            FormatErrorKind::NotEnoughData => "not enough data left in the data subchunk",
+            FormatErrorKind::MissingDs64Chunk => "missing or incomplete 'ds64' chunk required by RF64/BW64 files",
+            FormatErrorKind::InvalidAdpcmBlock => "malformed Microsoft ADPCM block",
+            FormatErrorKind::BextChunkTooShort => "bext chunk is too short",
+            FormatErrorKind::InvalidChannelCount => "fmt chunk declares zero channels",
         }
     }
 }
@@ -83,21 +102,162 @@ impl From<io::Error> for ReadError {
     }
 }
 
+/// Represents an error that occurred while writing a wave file.
+#[derive(Debug)]
+pub enum WriteError {
+    /// The given sample value doesn't fit within the configured `bits_per_sample`.
+    SampleOutOfRange(i32),
+    /// This crate doesn't support writing the requested `bits_per_sample`.
+    UnsupportedBitsPerSample(u16),
+    /// `num_channels` is too large for the configured `bits_per_sample`: the frame size in
+    /// bytes (`num_channels` times `bits_per_sample / 8`, the "block align" field) must fit in
+    /// 16 bits.
+    TooManyChannelsForBitsPerSample(u16),
+    /// `sample_rate` is too high for the configured channels/`bits_per_sample`: their product
+    /// (the "byte rate" field) must fit in 32 bits.
+    SampleRateTooHighForBlockAlign(u32),
+    /// An IO error occurred.
+    Io(io::Error),
+}
+
+/// Represents a result when writing a wave file.
+pub type WriteResult<T> = result::Result<T, WriteError>;
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WriteError::SampleOutOfRange(sample) => {
+                write!(f, "sample {} is out of range for the configured bits per sample", sample)
+            }
+            WriteError::UnsupportedBitsPerSample(bits) => {
+                write!(f, "{} bits per sample is not supported", bits)
+            }
+            WriteError::TooManyChannelsForBitsPerSample(num_channels) => {
+                write!(f,
+                       "{} channels is too many for the configured bits per sample",
+                       num_channels)
+            }
+            WriteError::SampleRateTooHighForBlockAlign(sample_rate) => {
+                write!(f,
+                       "sample rate {} is too high for the configured channels/bits per sample",
+                       sample_rate)
+            }
+            WriteError::Io(ref err) => write!(f, "IO error: {}", err),
+        }
+    }
+}
+
+impl error::Error for WriteError {
+    fn description(&self) -> &str {
+        match *self {
+            WriteError::SampleOutOfRange(_) => "sample out of range",
+            WriteError::UnsupportedBitsPerSample(_) => "unsupported bits per sample",
+            WriteError::TooManyChannelsForBitsPerSample(_) => {
+                "too many channels for the configured bits per sample"
+            }
+            WriteError::SampleRateTooHighForBlockAlign(_) => {
+                "sample rate is too high for the configured channels/bits per sample"
+            }
+            WriteError::Io(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            WriteError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> WriteError {
+        WriteError::Io(err)
+    }
+}
+
 // MARK: Validation and parsing functions
 
 const FORMAT_UNCOMPRESSED_PCM: u16 = 1;
+const FORMAT_MS_ADPCM: u16 = 2;
 const FORMAT_EXTENDED: u16 = 65534;
 
+// The size, in bytes, of a canonical "fmt " chunk: format tag, channel count, sample rate,
+// byte rate, block align, and bits per sample.
+const CANONICAL_FMT_SIZE: u32 = 16;
+
+// The size, in bytes, of the "cbSize" field that precedes the WAVE_FORMAT_EXTENSIBLE
+// extension in a "fmt " chunk.
+const EXTENSIBLE_FMT_CBSIZE_FIELD_SIZE: u32 = 2;
+
+// The minimum value "cbSize" must declare for a WAVE_FORMAT_EXTENSIBLE extension: valid bits
+// per sample, channel mask, and the 16-byte sub-format GUID.
+const EXTENSIBLE_FMT_EXTENSION_MIN_SIZE: u32 = 22;
+
+// The chunk size value that marks an RF64/BW64 chunk's real size as living in the "ds64"
+// chunk instead, since it doesn't fit in 32 bits.
+const RF64_SIZE_PLACEHOLDER: u32 = 0xffff_ffff;
+
+// The byte offset of the first subchunk after the "RIFF"/"RF64" tag, its chunk size, and the
+// "WAVE" tag: 4 + 4 + 4.
+const WAVE_TAG_END_OFFSET: u64 = 12;
+
+// The minimum size, in bytes, of a Microsoft ADPCM "fmt " extension: wSamplesPerBlock and
+// wNumCoef, not counting the coefficient table itself (whose length depends on wNumCoef).
+const MS_ADPCM_EXTENSION_MIN_SIZE: u32 = 4;
+
+// The size, in bytes, of the per-channel block preamble: a predictor index, a delta, and
+// two previous samples.
+const MS_ADPCM_PREAMBLE_SIZE_PER_CHANNEL: usize = 7;
+
+// The adaptation coefficients used to update a channel's delta after each decoded nibble, as
+// defined by the Microsoft ADPCM format.
+const MS_ADPCM_ADAPTATION_TABLE: [i32; 16] =
+    [230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230];
+
+/// Identifies which kind of RIFF container a file starts with.
+#[derive(Debug)]
+enum RiffContainer {
+    /// A standard "RIFF" file, limited to chunk sizes that fit in 32 bits.
+    Riff,
+    /// An "RF64" or "BW64" file, whose chunk sizes are resolved through a "ds64" chunk.
+    Rf64,
+}
+
+// The 64-bit chunk sizes declared in an RF64/BW64 file's "ds64" chunk. `data_size` stands
+// in for the "data" chunk's size; `chunk_sizes` resolves the size of any other oversized
+// chunk by tag.
+#[derive(Debug, Clone)]
+struct Ds64 {
+    data_size: u64,
+    chunk_sizes: Vec<([u8; 4], u64)>,
+}
+
+impl Ds64 {
+    fn size_of(&self, tag: &[u8; 4]) -> ReadResult<u64> {
+        if tag == b"data" {
+            return Ok(self.data_size);
+        }
+        self.chunk_sizes
+            .iter()
+            .find(|&&(ref chunk_tag, _)| chunk_tag == tag)
+            .map(|&(_, size)| size)
+            .ok_or(ReadError::Format(FormatErrorKind::MissingDs64Chunk))
+    }
+}
+
 #[derive(Debug)]
 enum Format {
     UncompressedPcm,
     Extended,
+    MsAdpcm,
 }
 
 fn validate_pcm_format(format: u16) -> ReadResult<Format> {
     match format {
         FORMAT_UNCOMPRESSED_PCM => Ok(Format::UncompressedPcm),
         FORMAT_EXTENDED => Ok(Format::Extended),
+        FORMAT_MS_ADPCM => Ok(Format::MsAdpcm),
         _ => Err(ReadError::Format(FormatErrorKind::NotAnUncompressedPcmWaveFile(format))),
     }
 }
@@ -117,14 +277,50 @@ fn validate_fmt_header_is_large_enough(size: u32, min_size: u32) -> ReadResult<(
     }
 }
 
-trait WaveReader: Read + Seek {
-    fn validate_is_riff_file(&mut self) -> ReadResult<()> {
-        try!(self.validate_tag(b"RIFF", FormatErrorKind::NotARiffFile));
+fn validate_bits_per_sample_for_writing(bits_per_sample: u16) -> WriteResult<()> {
+    match bits_per_sample {
+        8 | 16 | 24 | 32 => Ok(()),
+        other => Err(WriteError::UnsupportedBitsPerSample(other)),
+    }
+}
+
+// Computes the "block align" field (the size, in bytes, of one frame) as a u32 so the
+// multiplication can't silently wrap, then checks it still fits in the u16 the header requires.
+fn block_align_for_writing(num_channels: u16, bits_per_sample: u16) -> WriteResult<u16> {
+    let block_align = (num_channels as u32) * (bits_per_sample as u32 / 8);
+    if block_align > u16::max_value() as u32 {
+        return Err(WriteError::TooManyChannelsForBitsPerSample(num_channels));
+    }
+    Ok(block_align as u16)
+}
+
+// Computes the "byte rate" field, rejecting `sample_rate`/`block_align` combinations whose
+// product doesn't fit in the u32 the header requires, rather than panicking on overflow.
+fn byte_rate_for_writing(sample_rate: u32, block_align: u16) -> WriteResult<u32> {
+    sample_rate.checked_mul(block_align as u32)
+        .ok_or(WriteError::SampleRateTooHighForBlockAlign(sample_rate))
+}
+
+// MARK: Low-level chunk reading
+
+// Provides the low-level RIFF chunk reading primitives shared by the header parser and the
+// sample reader. This is kept private; `WaveReader` is the public entry point.
+trait ChunkReader: Read + Seek {
+    fn validate_is_riff_file(&mut self) -> ReadResult<RiffContainer> {
+        let tag = try!(self.read_tag());
+        let container = if &tag == b"RIFF" {
+            RiffContainer::Riff
+        } else if &tag == b"RF64" || &tag == b"BW64" {
+            RiffContainer::Rf64
+        } else {
+            return Err(ReadError::Format(FormatErrorKind::NotARiffFile));
+        };
         // The next four bytes represent the chunk size. We're not going to
         // validate it, so that we can still try to read files that might have
-        // an incorrect chunk size, so let's skip over it.
+        // an incorrect chunk size, so let's skip over it. For RF64/BW64 files this is the
+        // 0xffffffff placeholder; the real size lives in the "ds64" chunk.
         let _ = try!(self.read_chunk_size());
-        Ok(())
+        Ok(container)
     }
 
     fn validate_is_wave_file(&mut self) -> ReadResult<()> {
@@ -143,19 +339,91 @@ trait WaveReader: Read + Seek {
         Ok(())
     }
 
-    fn skip_until_subchunk(&mut self, matching_tag: &[u8; 4]) -> ReadResult<u32> {
+    // Reads the "ds64" chunk that must immediately follow the "WAVE" tag in an RF64/BW64
+    // file, which carries 64-bit replacements for the RIFF, "data", and sample count sizes.
+    fn read_ds64_chunk(&mut self) -> ReadResult<Ds64> {
+        try!(self.validate_tag(b"ds64", FormatErrorKind::MissingDs64Chunk));
+        let chunk_size = try!(self.read_chunk_size()) as u64;
+
+        let _riff_size = try!(self.read_u64::<LittleEndian>());
+        let data_size = try!(self.read_u64::<LittleEndian>());
+        let _sample_count = try!(self.read_u64::<LittleEndian>());
+        let table_length = try!(self.read_u32::<LittleEndian>()) as u64;
+
+        // "ds64" is at least 28 bytes (riffSize/dataSize/sampleCount/tableLength), and the
+        // chunk-size table contributes 12 bytes per entry. Validate `table_length` against
+        // the chunk's own declared size before trusting it enough to pre-allocate for it.
+        let consumed = 28 + table_length * 12;
+        if consumed > chunk_size {
+            return Err(ReadError::Format(FormatErrorKind::MissingDs64Chunk));
+        }
+
+        // `chunk_size` itself is just another untrusted value read straight from the file, so
+        // `table_length` being internally consistent with it isn't enough to trust it for
+        // pre-allocation: don't reserve space up front, and let the Vec grow only as entries are
+        // actually read from the stream.
+        let mut chunk_sizes = Vec::new();
+        for _ in 0..table_length {
+            let chunk_tag = try!(self.read_tag());
+            let chunk_size = try!(self.read_u64::<LittleEndian>());
+            chunk_sizes.push((chunk_tag, chunk_size));
+        }
+
+        // "ds64" is a regular chunk, so it's also subject to word-alignment padding.
+        let remaining = chunk_size - consumed;
+        let pad = chunk_size & 1;
+        if remaining + pad > 0 {
+            try!(self.seek(SeekFrom::Current((remaining + pad) as i64)));
+        }
+
+        Ok(Ds64 {
+            data_size: data_size,
+            chunk_sizes: chunk_sizes,
+        })
+    }
+
+    fn skip_until_subchunk(&mut self,
+                           matching_tag: &[u8; 4],
+                           ds64: Option<&Ds64>)
+                           -> ReadResult<u64> {
         loop {
             let tag = try!(self.read_tag());
-            let subchunk_size = try!(self.read_chunk_size());
+            let subchunk_size = try!(self.resolve_chunk_size(&tag, ds64));
 
             if &tag == matching_tag {
                 return Ok(subchunk_size);
             } else {
-                try!(self.seek(SeekFrom::Current(subchunk_size.into())));
+                // Chunks are word-aligned: an odd-sized chunk is followed by a single pad
+                // byte that isn't counted in its size field.
+                let padded_size = subchunk_size + (subchunk_size & 1);
+                try!(self.seek(SeekFrom::Current(padded_size as i64)));
             }
         }
     }
 
+    fn resolve_chunk_size(&mut self, tag: &[u8; 4], ds64: Option<&Ds64>) -> ReadResult<u64> {
+        let declared_size = try!(self.read_chunk_size());
+        if declared_size != RF64_SIZE_PLACEHOLDER {
+            return Ok(declared_size as u64);
+        }
+        match ds64 {
+            Some(ds64) => ds64.size_of(tag),
+            None => Err(ReadError::Format(FormatErrorKind::MissingDs64Chunk)),
+        }
+    }
+
+    // Generalizes `skip_until_subchunk` into an iterator over every subchunk from the current
+    // position onward, so callers can discover chunks this crate doesn't hard-code handling
+    // for, like "bext", "LIST"/"INFO", "cue ", or "fact".
+    fn chunks(&mut self, ds64: Option<Ds64>) -> Chunks<Self>
+        where Self: Sized
+    {
+        Chunks {
+            reader: self,
+            ds64: ds64,
+        }
+    }
+
     fn read_tag(&mut self) -> ReadResult<[u8; 4]> {
         let mut tag: [u8; 4] = [0; 4];
         try!(self.read_exact(&mut tag));
@@ -167,7 +435,866 @@ trait WaveReader: Read + Seek {
     }
 }
 
-impl<T> WaveReader for T where T: Read + Seek {}
+impl<T> ChunkReader for T where T: Read + Seek {}
+
+// One subchunk discovered while walking a RIFF container: its four-byte tag, its size in
+// bytes (not counting the word-alignment pad byte), and its byte offset from the start of
+// the file, i.e. where its tag begins.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkInfo {
+    /// The subchunk's four-byte tag, e.g. `b"bext"` or `b"cue "`.
+    pub tag: [u8; 4],
+    /// The subchunk's size in bytes, as declared in its header (or resolved via "ds64" for
+    /// RF64/BW64 files).
+    pub size: u64,
+    /// The byte offset, from the start of the file, of this subchunk's tag.
+    pub offset: u64,
+}
+
+/// An iterator over every subchunk in a RIFF container, reporting each one's tag, size, and
+/// offset without otherwise interpreting its contents.
+///
+/// Created by `WaveReader::chunks()`. Useful for discovering and seeking to chunks this crate
+/// doesn't parse itself, like "LIST"/"INFO", "cue ", or "fact".
+pub struct Chunks<'a, T>
+    where T: Read + Seek + 'a
+{
+    reader: &'a mut T,
+    ds64: Option<Ds64>,
+}
+
+impl<'a, T> Iterator for Chunks<'a, T>
+    where T: Read + Seek
+{
+    type Item = ReadResult<ChunkInfo>;
+
+    fn next(&mut self) -> Option<ReadResult<ChunkInfo>> {
+        let offset = match self.reader.seek(SeekFrom::Current(0)) {
+            Ok(pos) => pos,
+            Err(err) => return Some(Err(ReadError::Io(err))),
+        };
+
+        let tag = match self.reader.read_tag() {
+            Ok(tag) => tag,
+            Err(ReadError::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        };
+
+        let size = match self.reader.resolve_chunk_size(&tag, self.ds64.as_ref()) {
+            Ok(size) => size,
+            Err(err) => return Some(Err(err)),
+        };
+
+        // Chunks are word-aligned: an odd-sized chunk is followed by a single pad byte that
+        // isn't counted in its size field.
+        let padded_size = size + (size & 1);
+        if let Err(err) = self.reader.seek(SeekFrom::Current(padded_size as i64)) {
+            return Some(Err(ReadError::from(err)));
+        }
+
+        Some(Ok(ChunkInfo {
+            tag: tag,
+            size: size,
+            offset: offset,
+        }))
+    }
+}
+
+// MARK: PCM format
+
+/// Contains the PCM format information read from a wave file's "fmt " chunk.
+#[derive(Debug)]
+pub struct PcmFormat {
+    /// The number of audio channels, e.g. 1 for mono or 2 for stereo.
+    pub num_channels: u16,
+    /// The sample rate, in samples per second.
+    pub sample_rate: u32,
+    /// The number of bits used to store each sample, i.e. the container size.
+    pub bits_per_sample: u16,
+    /// The number of bits that are actually significant, which may be smaller than
+    /// `bits_per_sample`. Only present for WAVE_FORMAT_EXTENSIBLE files.
+    pub valid_bits_per_sample: Option<u16>,
+    /// A bitfield mapping channels to speaker positions. Only present for
+    /// WAVE_FORMAT_EXTENSIBLE files.
+    pub channel_mask: Option<u32>,
+    /// The 16-byte sub-format GUID identifying the sample encoding. Only present for
+    /// WAVE_FORMAT_EXTENSIBLE files.
+    pub sub_format: Option<[u8; 16]>,
+}
+
+impl PcmFormat {
+    fn new() -> PcmFormat {
+        PcmFormat {
+            num_channels: 0,
+            sample_rate: 0,
+            bits_per_sample: 0,
+            valid_bits_per_sample: None,
+            channel_mask: None,
+            sub_format: None,
+        }
+    }
+}
+
+// MARK: Microsoft ADPCM decoding
+
+// The coefficient pairs and block layout read from a Microsoft ADPCM "fmt " extension,
+// needed to decode the "data" subchunk's compressed blocks into PCM samples.
+#[derive(Debug)]
+struct AdpcmFormat {
+    samples_per_block: u16,
+    block_align: u16,
+    coefficients: Vec<(i32, i32)>,
+}
+
+// Sign-extends a 4-bit nibble (0..=15) to a full `i32`.
+fn sign_extend_adpcm_nibble(nibble: u8) -> i32 {
+    let value = nibble as i32;
+    if value >= 8 {
+        value - 16
+    } else {
+        value
+    }
+}
+
+// Clamps a predicted sample to the range of an `i16`, since Microsoft ADPCM samples are
+// always 16-bit once decoded.
+fn clamp_to_i16_range(value: i32) -> i32 {
+    if value < i16::min_value() as i32 {
+        i16::min_value() as i32
+    } else if value > i16::max_value() as i32 {
+        i16::max_value() as i32
+    } else {
+        value
+    }
+}
+
+// Walks a byte slice one 4-bit nibble at a time, high nibble first.
+struct NibbleReader<'a> {
+    bytes: &'a [u8],
+    byte_index: usize,
+    high_nibble_next: bool,
+}
+
+impl<'a> NibbleReader<'a> {
+    fn new(bytes: &'a [u8]) -> NibbleReader<'a> {
+        NibbleReader {
+            bytes: bytes,
+            byte_index: 0,
+            high_nibble_next: true,
+        }
+    }
+
+    fn next_nibble(&mut self) -> ReadResult<u8> {
+        if self.byte_index >= self.bytes.len() {
+            return Err(ReadError::Format(FormatErrorKind::InvalidAdpcmBlock));
+        }
+
+        let byte = self.bytes[self.byte_index];
+        if self.high_nibble_next {
+            self.high_nibble_next = false;
+            Ok((byte >> 4) & 0x0f)
+        } else {
+            self.high_nibble_next = true;
+            self.byte_index += 1;
+            Ok(byte & 0x0f)
+        }
+    }
+}
+
+// Decodes a single Microsoft ADPCM block into interleaved PCM samples. A block holds a
+// per-channel preamble (predictor index, delta, and the two most recent samples) followed by
+// 4-bit nibbles, interleaved channel-by-channel, each encoding one more sample.
+fn decode_adpcm_block(block: &[u8],
+                      num_channels: usize,
+                      samples_per_block: usize,
+                      coefficients: &[(i32, i32)])
+                      -> ReadResult<Vec<i32>> {
+    let preamble_size = num_channels * MS_ADPCM_PREAMBLE_SIZE_PER_CHANNEL;
+    if block.len() < preamble_size {
+        return Err(ReadError::Format(FormatErrorKind::InvalidAdpcmBlock));
+    }
+
+    let mut preamble = Cursor::new(&block[..preamble_size]);
+
+    let mut predictors = Vec::with_capacity(num_channels);
+    for _ in 0..num_channels {
+        let predictor = try!(preamble.read_u8()) as usize;
+        let &(coef1, coef2) = try!(coefficients.get(predictor)
+            .ok_or(ReadError::Format(FormatErrorKind::InvalidAdpcmBlock)));
+        predictors.push((coef1, coef2));
+    }
+
+    let mut deltas = Vec::with_capacity(num_channels);
+    for _ in 0..num_channels {
+        deltas.push(try!(preamble.read_i16::<LittleEndian>()) as i32);
+    }
+
+    let mut sample1s = Vec::with_capacity(num_channels);
+    for _ in 0..num_channels {
+        sample1s.push(try!(preamble.read_i16::<LittleEndian>()) as i32);
+    }
+
+    let mut sample2s = Vec::with_capacity(num_channels);
+    for _ in 0..num_channels {
+        sample2s.push(try!(preamble.read_i16::<LittleEndian>()) as i32);
+    }
+
+    // `samples_per_block` is an attacker-controlled field that's never cross-checked against
+    // how much nibble data the block actually holds, so don't let it size the allocation
+    // directly: bound it by `nibble_bytes.len()`, the data that's actually in hand.
+    let nibble_bytes = &block[preamble_size..];
+    let target_samples = samples_per_block.saturating_sub(2) * num_channels;
+    let available_samples = (nibble_bytes.len() * 2 / num_channels) * num_channels;
+    let remaining_samples = if target_samples < available_samples { target_samples } else { available_samples };
+
+    let mut samples = Vec::with_capacity(2 * num_channels + remaining_samples);
+    for channel in 0..num_channels {
+        samples.push(sample2s[channel]);
+    }
+    for channel in 0..num_channels {
+        samples.push(sample1s[channel]);
+    }
+
+    let mut nibble_reader = NibbleReader::new(nibble_bytes);
+    for i in 0..remaining_samples {
+        let channel = i % num_channels;
+        let nibble = try!(nibble_reader.next_nibble());
+
+        let (coef1, coef2) = predictors[channel];
+        let delta = deltas[channel];
+        let error = sign_extend_adpcm_nibble(nibble);
+
+        // `coef1`/`coef2`/`sample1`/`sample2` are all attacker-controlled, and can combine to
+        // overflow i32 (e.g. coef == sample == i16::min_value() for both terms). Use checked
+        // arithmetic throughout and report overflow as a malformed block rather than panicking.
+        let predicted = try!(checked_adpcm_arith(sample1s[channel].checked_mul(coef1)
+            .and_then(|a| sample2s[channel].checked_mul(coef2).and_then(|b| a.checked_add(b)))))
+            >> 8;
+        let error_delta = try!(checked_adpcm_arith(error.checked_mul(delta)));
+        let new_sample = clamp_to_i16_range(try!(checked_adpcm_arith(predicted.checked_add(error_delta))));
+
+        samples.push(new_sample);
+        sample2s[channel] = sample1s[channel];
+        sample1s[channel] = new_sample;
+
+        let adapted_delta =
+            try!(checked_adpcm_arith(MS_ADPCM_ADAPTATION_TABLE[nibble as usize].checked_mul(delta))) >> 8;
+        deltas[channel] = if adapted_delta > 16 { adapted_delta } else { 16 };
+    }
+
+    Ok(samples)
+}
+
+// Unwraps a checked arithmetic result, reporting overflow as a malformed ADPCM block rather
+// than letting the caller panic on attacker-controlled coefficients/deltas/samples.
+fn checked_adpcm_arith(value: Option<i32>) -> ReadResult<i32> {
+    value.ok_or(ReadError::Format(FormatErrorKind::InvalidAdpcmBlock))
+}
+
+// MARK: Broadcast Wave metadata
+
+// The size, in bytes, of the "bext" chunk's fixed-length fields, before "CodingHistory"
+// begins: Description(256) + Originator(32) + OriginatorReference(32) + OriginationDate(10) +
+// OriginationTime(8) + TimeReferenceLow(4) + TimeReferenceHigh(4) + Version(2) + UMID(64) +
+// five WORD loudness fields(10) + Reserved(180).
+const BEXT_FIXED_SIZE: u64 = 602;
+
+// The number of fixed-length bytes this crate actually reads before skipping the rest of the
+// fixed-length fields it doesn't expose (version, UMID, loudness, and reserved bytes).
+const BEXT_FIELDS_READ: u64 = 346;
+
+/// Broadcast Wave Format metadata, read from a file's "bext" chunk (EBU Tech 3285).
+#[derive(Debug, Clone)]
+pub struct BextMetadata {
+    /// A free-text description of the sound sequence.
+    pub description: [u8; 256],
+    /// The name of the originator/producer of the audio file.
+    pub originator: [u8; 32],
+    /// An unambiguous reference allocated by the originating organization.
+    pub originator_reference: [u8; 32],
+    /// The date of creation of the audio sequence, formatted "yyyy-mm-dd".
+    pub origination_date: [u8; 10],
+    /// The time of creation of the audio sequence, formatted "hh-mm-ss".
+    pub origination_time: [u8; 8],
+    /// The first sample count since midnight, i.e. the sample position of this file's start
+    /// relative to the start of the day it was recorded.
+    pub time_reference: u64,
+    /// A free-text history of the coding processes applied to the audio data.
+    pub coding_history: Vec<u8>,
+}
+
+// Reads a "bext" chunk's metadata; `reader` must be positioned right after its tag and size,
+// and `chunk_size` is that chunk's declared size (not including the word-alignment pad byte).
+fn read_bext_metadata<T>(reader: &mut T, chunk_size: u64) -> ReadResult<BextMetadata>
+    where T: Read + Seek
+{
+    if chunk_size < BEXT_FIXED_SIZE {
+        return Err(ReadError::Format(FormatErrorKind::BextChunkTooShort));
+    }
+
+    let mut description = [0u8; 256];
+    try!(reader.read_exact(&mut description));
+    let mut originator = [0u8; 32];
+    try!(reader.read_exact(&mut originator));
+    let mut originator_reference = [0u8; 32];
+    try!(reader.read_exact(&mut originator_reference));
+    let mut origination_date = [0u8; 10];
+    try!(reader.read_exact(&mut origination_date));
+    let mut origination_time = [0u8; 8];
+    try!(reader.read_exact(&mut origination_time));
+    let time_reference_low = try!(reader.read_u32::<LittleEndian>()) as u64;
+    let time_reference_high = try!(reader.read_u32::<LittleEndian>()) as u64;
+    let time_reference = time_reference_low | (time_reference_high << 32);
+
+    // Skip the version, UMID, loudness fields, and reserved bytes, which this crate doesn't
+    // expose yet.
+    try!(reader.seek(SeekFrom::Current((BEXT_FIXED_SIZE - BEXT_FIELDS_READ) as i64)));
+
+    // `chunk_size` is the chunk's declared size, which for RF64/BW64 files can come from a
+    // "ds64" chunk-size table entry and isn't otherwise bounded. Rather than trusting it enough
+    // to pre-allocate a `Vec` of that size up front, read "CodingHistory" through a `Take` so the
+    // amount actually allocated tracks the bytes actually available, not the declared size.
+    let coding_history_size = chunk_size - BEXT_FIXED_SIZE;
+    let mut coding_history = Vec::new();
+    try!(reader.take(coding_history_size).read_to_end(&mut coding_history));
+    if (coding_history.len() as u64) < coding_history_size {
+        return Err(ReadError::Format(FormatErrorKind::BextChunkTooShort));
+    }
+
+    Ok(BextMetadata {
+        description: description,
+        originator: originator,
+        originator_reference: originator_reference,
+        origination_date: origination_date,
+        origination_time: origination_time,
+        time_reference: time_reference,
+        coding_history: coding_history,
+    })
+}
+
+// MARK: WaveReader
+
+/// Reads the header of a wave file and allows its PCM audio samples to be read afterwards.
+///
+/// Uncompressed PCM wave files are supported, as is Microsoft ADPCM (format tag 2), which is
+/// transparently decoded to PCM samples by `read_sample()`/`frames()`.
+#[derive(Debug)]
+pub struct WaveReader<T>
+    where T: Read + Seek
+{
+    reader: T,
+    /// The PCM format used by this wave file, read from its "fmt " chunk.
+    pub pcm_format: PcmFormat,
+    data_size: u64,
+    bytes_read: u64,
+    data_pad_byte_skipped: bool,
+    adpcm_format: Option<AdpcmFormat>,
+    adpcm_pending_samples: Vec<i32>,
+    adpcm_pending_index: usize,
+    // Kept around so `chunks()` can resolve RF64/BW64 chunk sizes after construction.
+    ds64: Option<Ds64>,
+}
+
+impl<T> WaveReader<T>
+    where T: Read + Seek
+{
+    /// Creates a new `WaveReader`, validating the RIFF/WAVE header and parsing the "fmt "
+    /// chunk. After construction, the reader is positioned at the start of the "data"
+    /// subchunk's samples, ready for `read_sample()` or `frames()`.
+    ///
+    /// Both standard "RIFF" files and 64-bit "RF64"/"BW64" files (for audio larger than
+    /// 4 GB) are supported; the latter must carry a "ds64" chunk immediately after "WAVE".
+    pub fn new(mut reader: T) -> ReadResult<WaveReader<T>> {
+        let container = try!(reader.validate_is_riff_file());
+        try!(reader.validate_is_wave_file());
+
+        let ds64 = match container {
+            RiffContainer::Rf64 => Some(try!(reader.read_ds64_chunk())),
+            RiffContainer::Riff => None,
+        };
+
+        let fmt_size = try!(reader.skip_until_subchunk(b"fmt ", ds64.as_ref()));
+        try!(validate_fmt_header_is_large_enough(fmt_size as u32, CANONICAL_FMT_SIZE));
+
+        let format = try!(validate_pcm_format(try!(reader.read_u16::<LittleEndian>())));
+
+        let mut pcm_format = PcmFormat::new();
+        pcm_format.num_channels = try!(reader.read_u16::<LittleEndian>());
+        if pcm_format.num_channels == 0 {
+            // A zero channel count would make every per-channel computation downstream
+            // (frame buffers, ADPCM decoding) either divide by zero or silently do nothing.
+            return Err(ReadError::Format(FormatErrorKind::InvalidChannelCount));
+        }
+        pcm_format.sample_rate = try!(reader.read_u32::<LittleEndian>());
+        let _byte_rate = try!(reader.read_u32::<LittleEndian>());
+        let block_align = try!(reader.read_u16::<LittleEndian>());
+        pcm_format.bits_per_sample = try!(reader.read_u16::<LittleEndian>());
+
+        let mut adpcm_format = None;
+
+        if let Format::MsAdpcm = format {
+            // There must be room for the "cbSize" field itself before we can even read it.
+            try!(validate_fmt_header_is_large_enough(fmt_size as u32,
+                                                      CANONICAL_FMT_SIZE +
+                                                      EXTENSIBLE_FMT_CBSIZE_FIELD_SIZE));
+            let cb_size = try!(reader.read_u16::<LittleEndian>()) as u32;
+            // "cbSize" must declare at least enough room for wSamplesPerBlock/wNumCoef...
+            try!(validate_fmt_header_is_large_enough(cb_size, MS_ADPCM_EXTENSION_MIN_SIZE));
+            try!(validate_fmt_header_is_large_enough(fmt_size as u32,
+                                                      CANONICAL_FMT_SIZE +
+                                                      EXTENSIBLE_FMT_CBSIZE_FIELD_SIZE +
+                                                      MS_ADPCM_EXTENSION_MIN_SIZE));
+
+            let samples_per_block = try!(reader.read_u16::<LittleEndian>());
+            let num_coef = try!(reader.read_u16::<LittleEndian>());
+
+            // ...and then enough room for the coefficient table, whose size depends on wNumCoef.
+            let coef_table_size = (num_coef as u32) * 4;
+            try!(validate_fmt_header_is_large_enough(cb_size,
+                                                      MS_ADPCM_EXTENSION_MIN_SIZE + coef_table_size));
+            try!(validate_fmt_header_is_large_enough(fmt_size as u32,
+                                                      CANONICAL_FMT_SIZE +
+                                                      EXTENSIBLE_FMT_CBSIZE_FIELD_SIZE +
+                                                      MS_ADPCM_EXTENSION_MIN_SIZE + coef_table_size));
+
+            let mut coefficients = Vec::with_capacity(num_coef as usize);
+            for _ in 0..num_coef {
+                let coef1 = try!(reader.read_i16::<LittleEndian>()) as i32;
+                let coef2 = try!(reader.read_i16::<LittleEndian>()) as i32;
+                coefficients.push((coef1, coef2));
+            }
+
+            // Skip any extension bytes beyond the ones we understand.
+            let extra = cb_size - MS_ADPCM_EXTENSION_MIN_SIZE - coef_table_size;
+            if extra > 0 {
+                try!(reader.seek(SeekFrom::Current(extra as i64)));
+            }
+
+            adpcm_format = Some(AdpcmFormat {
+                samples_per_block: samples_per_block,
+                block_align: block_align,
+                coefficients: coefficients,
+            });
+        }
+
+        if let Format::Extended = format {
+            // There must be room for the "cbSize" field itself before we can even read it.
+            try!(validate_fmt_header_is_large_enough(fmt_size as u32,
+                                                      CANONICAL_FMT_SIZE +
+                                                      EXTENSIBLE_FMT_CBSIZE_FIELD_SIZE));
+            let cb_size = try!(reader.read_u16::<LittleEndian>()) as u32;
+            // "cbSize" must declare at least enough room for the fields we understand...
+            try!(validate_fmt_header_is_large_enough(cb_size, EXTENSIBLE_FMT_EXTENSION_MIN_SIZE));
+            // ...and the chunk itself must actually be large enough to hold that extension.
+            try!(validate_fmt_header_is_large_enough(fmt_size as u32,
+                                                      CANONICAL_FMT_SIZE +
+                                                      EXTENSIBLE_FMT_CBSIZE_FIELD_SIZE + cb_size));
+
+            pcm_format.valid_bits_per_sample = Some(try!(reader.read_u16::<LittleEndian>()));
+            pcm_format.channel_mask = Some(try!(reader.read_u32::<LittleEndian>()));
+
+            let mut sub_format = [0u8; 16];
+            try!(reader.read_exact(&mut sub_format));
+            let sub_format_tag = (sub_format[0] as u16) | ((sub_format[1] as u16) << 8);
+            try!(validate_pcm_subformat(sub_format_tag));
+            pcm_format.sub_format = Some(sub_format);
+
+            // Skip any extension bytes beyond the ones we understand.
+            let extra = cb_size - EXTENSIBLE_FMT_EXTENSION_MIN_SIZE;
+            if extra > 0 {
+                try!(reader.seek(SeekFrom::Current(extra as i64)));
+            }
+        }
+
+        let data_size = try!(reader.skip_until_subchunk(b"data", ds64.as_ref()));
+
+        Ok(WaveReader {
+            reader: reader,
+            pcm_format: pcm_format,
+            data_size: data_size,
+            bytes_read: 0,
+            data_pad_byte_skipped: false,
+            adpcm_format: adpcm_format,
+            adpcm_pending_samples: Vec::new(),
+            adpcm_pending_index: 0,
+            ds64: ds64,
+        })
+    }
+
+    /// Reads a single sample, decoded according to `bits_per_sample`, and widened to `i32`.
+    ///
+    /// 8-bit samples are unsigned, as specified by the wave format; 16-, 24-, and 32-bit
+    /// samples are signed. Returns `FormatErrorKind::NotEnoughData` once the "data" subchunk
+    /// has been fully consumed, including when the underlying stream ends early.
+    pub fn read_sample(&mut self) -> ReadResult<i32> {
+        if self.adpcm_format.is_some() {
+            return self.read_adpcm_sample();
+        }
+
+        let sample_size = (self.pcm_format.bits_per_sample as u64 + 7) / 8;
+        if self.bytes_read + sample_size > self.data_size {
+            try!(self.skip_data_pad_byte());
+            return Err(ReadError::Format(FormatErrorKind::NotEnoughData));
+        }
+
+        let sample = match self.pcm_format.bits_per_sample {
+            8 => try!(self.checked_read_u8()) as i32,
+            16 => try!(self.checked_read_i16()) as i32,
+            24 => try!(self.checked_read_i24()),
+            32 => try!(self.checked_read_i32()),
+            other => {
+                return Err(ReadError::Format(FormatErrorKind::NotAnUncompressedPcmWaveFile(other as u16)))
+            }
+        };
+
+        self.bytes_read += sample_size;
+        Ok(sample)
+    }
+
+    // Serves the next decoded sample from the current Microsoft ADPCM block, decoding the
+    // next block from the "data" subchunk first if the current one has been fully consumed.
+    fn read_adpcm_sample(&mut self) -> ReadResult<i32> {
+        if self.adpcm_pending_index >= self.adpcm_pending_samples.len() {
+            try!(self.decode_next_adpcm_block());
+        }
+
+        if self.adpcm_pending_index >= self.adpcm_pending_samples.len() {
+            return Err(ReadError::Format(FormatErrorKind::NotEnoughData));
+        }
+
+        let sample = self.adpcm_pending_samples[self.adpcm_pending_index];
+        self.adpcm_pending_index += 1;
+        Ok(sample)
+    }
+
+    fn decode_next_adpcm_block(&mut self) -> ReadResult<()> {
+        let remaining = self.data_size.saturating_sub(self.bytes_read);
+        if remaining == 0 {
+            return Ok(());
+        }
+
+        let (block_align, samples_per_block) = {
+            let adpcm_format = self.adpcm_format.as_ref().expect("decode_next_adpcm_block requires adpcm_format");
+            (adpcm_format.block_align, adpcm_format.samples_per_block)
+        };
+        let coefficients = self.adpcm_format.as_ref().unwrap().coefficients.clone();
+
+        let block_size = if remaining < block_align as u64 { remaining } else { block_align as u64 } as usize;
+        let mut block = vec![0u8; block_size];
+        match self.reader.read_exact(&mut block) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(ReadError::Format(FormatErrorKind::NotEnoughData));
+            }
+            Err(err) => return Err(ReadError::Io(err)),
+        }
+        self.bytes_read += block_size as u64;
+        if self.bytes_read >= self.data_size {
+            try!(self.skip_data_pad_byte());
+        }
+
+        self.adpcm_pending_samples = try!(decode_adpcm_block(&block,
+                                                              self.pcm_format.num_channels as usize,
+                                                              samples_per_block as usize,
+                                                              &coefficients));
+        self.adpcm_pending_index = 0;
+        Ok(())
+    }
+
+    /// Creates a zero-filled buffer sized to hold one frame, i.e. one sample per channel.
+    pub fn create_frame_buffer(&self) -> Vec<i32> {
+        vec![0; self.pcm_format.num_channels as usize]
+    }
+
+    /// Returns an iterator that reads one frame (`num_channels` samples) at a time.
+    pub fn frames(&mut self) -> Frames<T> {
+        Frames { wave_reader: self }
+    }
+
+    /// Returns an iterator over every subchunk in this file, including ones this crate
+    /// doesn't otherwise parse, like "LIST"/"INFO", "cue ", or "fact".
+    ///
+    /// Calling this repositions the reader to just after the "WAVE" tag; resuming
+    /// `read_sample()`/`frames()` afterward requires re-locating the "data" subchunk, e.g. via
+    /// `ChunkInfo::offset`. Prefer `read_bext_chunk()` if you just want Broadcast Wave
+    /// metadata, since it restores the reader's position automatically.
+    pub fn chunks(&mut self) -> ReadResult<Chunks<T>> {
+        try!(self.reader.seek(SeekFrom::Start(WAVE_TAG_END_OFFSET)));
+        Ok(self.reader.chunks(self.ds64.clone()))
+    }
+
+    /// Reads this file's "bext" chunk, if it has one, returning its Broadcast Wave Format
+    /// metadata. The reader's position is left unchanged, so this can be called at any point,
+    /// including interleaved with `read_sample()`/`frames()`.
+    pub fn read_bext_chunk(&mut self) -> ReadResult<Option<BextMetadata>> {
+        let saved_position = try!(self.reader.seek(SeekFrom::Current(0)));
+
+        let bext_chunk = {
+            let mut chunks = try!(self.chunks());
+            let mut found = None;
+            for chunk in &mut chunks {
+                let chunk = try!(chunk);
+                if &chunk.tag == b"bext" {
+                    found = Some(chunk);
+                    break;
+                }
+            }
+            found
+        };
+
+        let metadata = match bext_chunk {
+            Some(chunk) => {
+                try!(self.reader.seek(SeekFrom::Start(chunk.offset + 8)));
+                Some(try!(read_bext_metadata(&mut self.reader, chunk.size)))
+            }
+            None => None,
+        };
+
+        try!(self.reader.seek(SeekFrom::Start(saved_position)));
+        Ok(metadata)
+    }
+
+    // The "data" subchunk is word-aligned like any other: if its size is odd, it's followed
+    // by a single pad byte that isn't included in the size field. Consume it once we've
+    // reached the end of the declared data, so the stream is left correctly aligned for
+    // whatever subchunk comes next.
+    fn skip_data_pad_byte(&mut self) -> ReadResult<()> {
+        if !self.data_pad_byte_skipped {
+            self.data_pad_byte_skipped = true;
+            if self.data_size & 1 == 1 {
+                try!(self.reader.seek(SeekFrom::Current(1)));
+            }
+        }
+        Ok(())
+    }
+
+    fn checked_read_u8(&mut self) -> ReadResult<u8> {
+        match self.reader.read_u8() {
+            Ok(value) => Ok(value),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(ReadError::Format(FormatErrorKind::NotEnoughData))
+            }
+            Err(err) => Err(ReadError::Io(err)),
+        }
+    }
+
+    fn checked_read_i16(&mut self) -> ReadResult<i16> {
+        match self.reader.read_i16::<LittleEndian>() {
+            Ok(value) => Ok(value),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(ReadError::Format(FormatErrorKind::NotEnoughData))
+            }
+            Err(err) => Err(ReadError::Io(err)),
+        }
+    }
+
+    fn checked_read_i32(&mut self) -> ReadResult<i32> {
+        match self.reader.read_i32::<LittleEndian>() {
+            Ok(value) => Ok(value),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(ReadError::Format(FormatErrorKind::NotEnoughData))
+            }
+            Err(err) => Err(ReadError::Io(err)),
+        }
+    }
+
+    // Reads a signed 24-bit little-endian sample, sign-extended to `i32`.
+    fn checked_read_i24(&mut self) -> ReadResult<i32> {
+        let mut buf = [0u8; 3];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(ReadError::Format(FormatErrorKind::NotEnoughData))
+            }
+            Err(err) => return Err(ReadError::Io(err)),
+        }
+
+        let unsigned = (buf[0] as i32) | ((buf[1] as i32) << 8) | ((buf[2] as i32) << 16);
+        if buf[2] & 0x80 != 0 {
+            Ok(unsigned | !0x00ff_ffff)
+        } else {
+            Ok(unsigned)
+        }
+    }
+}
+
+/// An iterator over the frames (one sample per channel) of a wave file's "data" subchunk.
+///
+/// Created by `WaveReader::frames()`.
+pub struct Frames<'a, T>
+    where T: Read + Seek + 'a
+{
+    wave_reader: &'a mut WaveReader<T>,
+}
+
+impl<'a, T> Iterator for Frames<'a, T>
+    where T: Read + Seek
+{
+    type Item = ReadResult<Vec<i32>>;
+
+    fn next(&mut self) -> Option<ReadResult<Vec<i32>>> {
+        // For ADPCM, `bytes_read` reaches `data_size` as soon as the last block is decoded,
+        // while that block's samples may still be waiting to be served.
+        let adpcm_samples_pending =
+            self.wave_reader.adpcm_pending_index < self.wave_reader.adpcm_pending_samples.len();
+        if self.wave_reader.bytes_read >= self.wave_reader.data_size && !adpcm_samples_pending {
+            if let Err(err) = self.wave_reader.skip_data_pad_byte() {
+                return Some(Err(err));
+            }
+            return None;
+        }
+
+        let mut frame = self.wave_reader.create_frame_buffer();
+        for channel in frame.iter_mut() {
+            match self.wave_reader.read_sample() {
+                Ok(sample) => *channel = sample,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        Some(Ok(frame))
+    }
+}
+
+// MARK: WaveWriter
+
+// The size, in bytes, of a full canonical wave file header: "RIFF" tag, RIFF chunk size,
+// "WAVE" tag, "fmt " tag, fmt chunk size, the 16-byte canonical fmt content, "data" tag, and
+// the data chunk size.
+const CANONICAL_HEADER_SIZE: u32 = 44;
+
+// The byte offset of the "data" chunk's size field within the canonical header above.
+const DATA_SIZE_FIELD_OFFSET: u64 = 40;
+
+/// Writes uncompressed PCM wave files.
+///
+/// Call `finalize()` once all samples have been written, to patch the RIFF and "data" chunk
+/// sizes and get the underlying writer back. If `finalize()` is never called, `Drop` does the
+/// same patching, discarding any error.
+#[derive(Debug)]
+pub struct WaveWriter<W>
+    where W: Write + Seek
+{
+    writer: Option<W>,
+    bits_per_sample: u16,
+    data_bytes_written: u32,
+}
+
+impl<W> WaveWriter<W>
+    where W: Write + Seek
+{
+    /// Creates a new `WaveWriter`, writing a canonical RIFF/WAVE header with placeholder
+    /// chunk sizes that `finalize()` (or `Drop`) will patch once the sample count is known.
+    ///
+    /// Supports 8-, 16-, 24-, and 32-bit uncompressed PCM.
+    pub fn new(mut writer: W,
+               num_channels: u16,
+               sample_rate: u32,
+               bits_per_sample: u16)
+               -> WriteResult<WaveWriter<W>> {
+        try!(validate_bits_per_sample_for_writing(bits_per_sample));
+
+        let block_align = try!(block_align_for_writing(num_channels, bits_per_sample));
+        let byte_rate = try!(byte_rate_for_writing(sample_rate, block_align));
+
+        try!(writer.write_all(b"RIFF"));
+        try!(writer.write_u32::<LittleEndian>(0)); // Patched by finalize().
+        try!(writer.write_all(b"WAVE"));
+        try!(writer.write_all(b"fmt "));
+        try!(writer.write_u32::<LittleEndian>(CANONICAL_FMT_SIZE));
+        try!(writer.write_u16::<LittleEndian>(FORMAT_UNCOMPRESSED_PCM));
+        try!(writer.write_u16::<LittleEndian>(num_channels));
+        try!(writer.write_u32::<LittleEndian>(sample_rate));
+        try!(writer.write_u32::<LittleEndian>(byte_rate));
+        try!(writer.write_u16::<LittleEndian>(block_align));
+        try!(writer.write_u16::<LittleEndian>(bits_per_sample));
+        try!(writer.write_all(b"data"));
+        try!(writer.write_u32::<LittleEndian>(0)); // Patched by finalize().
+
+        Ok(WaveWriter {
+            writer: Some(writer),
+            bits_per_sample: bits_per_sample,
+            data_bytes_written: 0,
+        })
+    }
+
+    /// Writes a single sample, narrowed to `bits_per_sample`. 8-bit samples are unsigned, as
+    /// specified by the wave format; 16-, 24-, and 32-bit samples are signed. Returns
+    /// `WriteError::SampleOutOfRange` instead of silently truncating an out-of-range value.
+    pub fn write_sample(&mut self, sample: i32) -> WriteResult<()> {
+        match self.bits_per_sample {
+            8 => {
+                if sample < 0 || sample > 255 {
+                    return Err(WriteError::SampleOutOfRange(sample));
+                }
+                try!(self.writer_mut().write_u8(sample as u8));
+            }
+            16 => {
+                if sample < i16::min_value() as i32 || sample > i16::max_value() as i32 {
+                    return Err(WriteError::SampleOutOfRange(sample));
+                }
+                try!(self.writer_mut().write_i16::<LittleEndian>(sample as i16));
+            }
+            24 => {
+                const MIN_24_BIT: i32 = -(1 << 23);
+                const MAX_24_BIT: i32 = (1 << 23) - 1;
+                if sample < MIN_24_BIT || sample > MAX_24_BIT {
+                    return Err(WriteError::SampleOutOfRange(sample));
+                }
+                let unsigned = sample & 0x00ff_ffff;
+                try!(self.writer_mut().write_u8((unsigned & 0xff) as u8));
+                try!(self.writer_mut().write_u8(((unsigned >> 8) & 0xff) as u8));
+                try!(self.writer_mut().write_u8(((unsigned >> 16) & 0xff) as u8));
+            }
+            32 => {
+                try!(self.writer_mut().write_i32::<LittleEndian>(sample));
+            }
+            other => unreachable!("bits_per_sample {} was already validated in new()", other),
+        }
+
+        self.data_bytes_written += (self.bits_per_sample as u32) / 8;
+        Ok(())
+    }
+
+    /// Patches the RIFF and "data" chunk sizes to reflect the samples written (adding the
+    /// word-alignment pad byte if needed), and returns the underlying writer.
+    pub fn finalize(mut self) -> WriteResult<W> {
+        try!(self.patch_chunk_sizes());
+        Ok(self.writer.take().expect("finalize() only takes the writer once"))
+    }
+
+    fn writer_mut(&mut self) -> &mut W {
+        self.writer.as_mut().expect("WaveWriter's writer is only removed by finalize()")
+    }
+
+    fn patch_chunk_sizes(&mut self) -> WriteResult<()> {
+        if self.data_bytes_written & 1 == 1 {
+            try!(self.writer_mut().write_u8(0));
+        }
+
+        let riff_size = (CANONICAL_HEADER_SIZE - 8) + self.data_bytes_written +
+                        (self.data_bytes_written & 1);
+
+        try!(self.writer_mut().seek(SeekFrom::Start(4)));
+        try!(self.writer_mut().write_u32::<LittleEndian>(riff_size));
+
+        let data_bytes_written = self.data_bytes_written;
+        try!(self.writer_mut().seek(SeekFrom::Start(DATA_SIZE_FIELD_OFFSET)));
+        try!(self.writer_mut().write_u32::<LittleEndian>(data_bytes_written));
+
+        try!(self.writer_mut().seek(SeekFrom::End(0)));
+        Ok(())
+    }
+}
+
+impl<W> Drop for WaveWriter<W>
+    where W: Write + Seek
+{
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.patch_chunk_sizes();
+        }
+    }
+}
 
 // MARK: Tests
 
@@ -175,9 +1302,11 @@ impl<T> WaveReader for T where T: Read + Seek {}
 mod tests {
     use std::io::Cursor;
 
-    use {FORMAT_UNCOMPRESSED_PCM, FORMAT_EXTENDED};
-    use {Format, FormatErrorKind, ReadError, WaveReader};
+    use {FORMAT_UNCOMPRESSED_PCM, FORMAT_EXTENDED, FORMAT_MS_ADPCM, RF64_SIZE_PLACEHOLDER};
+    use {Format, FormatErrorKind, ReadError, ChunkReader, RiffContainer};
     use {validate_fmt_header_is_large_enough, validate_pcm_format, validate_pcm_subformat};
+    use {WaveReader, WaveWriter, WriteError};
+    use {read_bext_metadata, BEXT_FIXED_SIZE};
 
     // This is a helper macro that helps us validate results in our tests.
     // Thank you bluss and durka42!
@@ -198,7 +1327,19 @@ mod tests {
     #[test]
     fn test_validate_is_riff_file_ok() {
         let mut data = Cursor::new(b"RIFF    ");
-        assert_matches!(Ok(()), data.validate_is_riff_file());
+        assert_matches!(Ok(RiffContainer::Riff), data.validate_is_riff_file());
+    }
+
+    #[test]
+    fn test_validate_is_riff_file_ok_rf64() {
+        let mut data = Cursor::new(b"RF64    ");
+        assert_matches!(Ok(RiffContainer::Rf64), data.validate_is_riff_file());
+    }
+
+    #[test]
+    fn test_validate_is_riff_file_ok_bw64() {
+        let mut data = Cursor::new(b"BW64    ");
+        assert_matches!(Ok(RiffContainer::Rf64), data.validate_is_riff_file());
     }
 
     #[test]
@@ -248,7 +1389,7 @@ mod tests {
         let mut data = Cursor::new(b"RIFF    WAVEfmt \x00\x00\x00\x00");
         let _ = data.validate_is_riff_file();
         let _ = data.validate_is_wave_file();
-        let size = data.skip_until_subchunk(b"fmt ");
+        let size = data.skip_until_subchunk(b"fmt ", None);
         assert_eq!(0, size.unwrap());
     }
 
@@ -258,8 +1399,8 @@ mod tests {
         let mut data = Cursor::new(b"RIFF    WAVEfmt \x00\x00\x00\x00data\x00\x00\x00\x00");
         let _ = data.validate_is_riff_file();
         let _ = data.validate_is_wave_file();
-        let _ = data.skip_until_subchunk(b"fmt ");
-        let size = data.skip_until_subchunk(b"data");
+        let _ = data.skip_until_subchunk(b"fmt ", None);
+        let size = data.skip_until_subchunk(b"data", None);
         assert_eq!(0, size.unwrap());
     }
 
@@ -270,8 +1411,19 @@ mod tests {
         let mut data = Cursor::new(b"RIFF    WAVEdata\x00\x00\x00\x00fmt \x00\x00\x00\x00");
         let _ = data.validate_is_riff_file();
         let _ = data.validate_is_wave_file();
-        let _ = data.skip_until_subchunk(b"fmt ");
-        let size = data.skip_until_subchunk(b"data");
+        let _ = data.skip_until_subchunk(b"fmt ", None);
+        let size = data.skip_until_subchunk(b"data", None);
+        assert_eq!(0, size.unwrap());
+    }
+
+    #[test]
+    fn test_skip_until_subchunk_word_aligns_odd_sized_chunks() {
+        // A 1-byte "JUNK" chunk is followed by a single pad byte, not counted in its size,
+        // before "fmt " begins.
+        let mut data = Cursor::new(b"RIFF    WAVEJUNK\x01\x00\x00\x00X\x00fmt \x00\x00\x00\x00");
+        let _ = data.validate_is_riff_file();
+        let _ = data.validate_is_wave_file();
+        let size = data.skip_until_subchunk(b"fmt ", None);
         assert_eq!(0, size.unwrap());
     }
 
@@ -289,6 +1441,11 @@ mod tests {
         assert_matches!(Ok(Format::Extended), validate_pcm_format(FORMAT_EXTENDED));
     }
 
+    #[test]
+    fn test_validate_pcm_format_ok_ms_adpcm() {
+        assert_matches!(Ok(Format::MsAdpcm), validate_pcm_format(FORMAT_MS_ADPCM));
+    }
+
     #[test]
     fn test_validate_pcm_format_err_not_uncompressed() {
         assert_matches!(Err(ReadError::Format(FormatErrorKind::NotAnUncompressedPcmWaveFile(_))),
@@ -331,4 +1488,626 @@ mod tests {
         assert_matches!(Err(ReadError::Format(FormatErrorKind::FmtChunkTooShort)),
                         validate_fmt_header_is_large_enough(14, 16));
     }
+
+    // WaveReader and sample reading tests.
+
+    fn canonical_header(num_channels: u16, sample_rate: u32, bits_per_sample: u16, data: &[u8]) -> Vec<u8> {
+        let block_align = num_channels * ((bits_per_sample + 7) / 8);
+        let byte_rate = sample_rate * block_align as u32;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]); // RIFF chunk size, unused by the reader.
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_UNCOMPRESSED_PCM.to_le_bytes());
+        bytes.extend_from_slice(&num_channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&byte_rate.to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&bits_per_sample.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    // The 14 bytes that follow the two-byte format tag in a standard sub-format GUID.
+    const SUB_FORMAT_GUID_SUFFIX: [u8; 14] =
+        [0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71];
+
+    fn extensible_header(num_channels: u16,
+                          sample_rate: u32,
+                          bits_per_sample: u16,
+                          valid_bits_per_sample: u16,
+                          channel_mask: u32,
+                          data: &[u8])
+                          -> Vec<u8> {
+        let block_align = num_channels * ((bits_per_sample + 7) / 8);
+        let byte_rate = sample_rate * block_align as u32;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]); // RIFF chunk size, unused by the reader.
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&40u32.to_le_bytes()); // 16 canonical + 2 cbSize + 22 extension.
+        bytes.extend_from_slice(&FORMAT_EXTENDED.to_le_bytes());
+        bytes.extend_from_slice(&num_channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&byte_rate.to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&bits_per_sample.to_le_bytes());
+        bytes.extend_from_slice(&22u16.to_le_bytes()); // cbSize
+        bytes.extend_from_slice(&valid_bits_per_sample.to_le_bytes());
+        bytes.extend_from_slice(&channel_mask.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_UNCOMPRESSED_PCM.to_le_bytes());
+        bytes.extend_from_slice(&SUB_FORMAT_GUID_SUFFIX);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_wave_reader_new_reads_extensible_fmt_chunk() {
+        let bytes = extensible_header(2, 48000, 24, 20, 0x3, &[]);
+        let wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(Some(20), wave_reader.pcm_format.valid_bits_per_sample);
+        assert_eq!(Some(0x3), wave_reader.pcm_format.channel_mask);
+
+        let sub_format = wave_reader.pcm_format.sub_format.unwrap();
+        assert_eq!(FORMAT_UNCOMPRESSED_PCM, (sub_format[0] as u16) | ((sub_format[1] as u16) << 8));
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_extensible_fmt_extension_missing() {
+        // A "fmt " chunk that claims to be extensible but has no room left for "cbSize".
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&17u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_EXTENDED.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&44100u32.to_le_bytes());
+        bytes.extend_from_slice(&88200u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::FmtChunkTooShort)),
+                        WaveReader::new(Cursor::new(bytes)));
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_extensible_fmt_extension_incomplete() {
+        // A "fmt " chunk whose "cbSize" declares fewer than the 22 bytes an extension needs.
+        let mut bytes = extensible_header(1, 44100, 16, 16, 0, &[]);
+        let cb_size_offset = 36;
+        bytes[cb_size_offset] = 10;
+        bytes[cb_size_offset + 1] = 0;
+
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::FmtChunkTooShort)),
+                        WaveReader::new(Cursor::new(bytes)));
+    }
+
+    #[test]
+    fn test_wave_reader_new_reads_pcm_format() {
+        let bytes = canonical_header(2, 44100, 16, &[]);
+        let wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(2, wave_reader.pcm_format.num_channels);
+        assert_eq!(44100, wave_reader.pcm_format.sample_rate);
+        assert_eq!(16, wave_reader.pcm_format.bits_per_sample);
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_zero_channels() {
+        // A zero channel count would make `create_frame_buffer()` return an empty `Vec`, so
+        // `frames()` would yield `Ok(vec![])` forever instead of ever making progress.
+        let bytes = canonical_header(0, 44100, 16, &[0x01, 0x02]);
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::InvalidChannelCount)),
+                        WaveReader::new(Cursor::new(bytes)));
+    }
+
+    #[test]
+    fn test_read_sample_16bit() {
+        let bytes = canonical_header(1, 44100, 16, &[0x01, 0x02, 0xff, 0x7f]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(0x0201, wave_reader.read_sample().unwrap());
+        assert_eq!(0x7fff, wave_reader.read_sample().unwrap());
+    }
+
+    #[test]
+    fn test_read_sample_8bit() {
+        let bytes = canonical_header(1, 44100, 8, &[0x00, 0xff]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(0, wave_reader.read_sample().unwrap());
+        assert_eq!(255, wave_reader.read_sample().unwrap());
+    }
+
+    #[test]
+    fn test_read_sample_24bit_sign_extends() {
+        let bytes = canonical_header(1, 44100, 24, &[0xff, 0xff, 0xff]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(-1, wave_reader.read_sample().unwrap());
+    }
+
+    #[test]
+    fn test_read_sample_err_not_enough_data() {
+        let bytes = canonical_header(1, 44100, 16, &[0x01]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::NotEnoughData)),
+                        wave_reader.read_sample());
+    }
+
+    #[test]
+    fn test_frames_groups_samples_by_channel() {
+        let bytes = canonical_header(2, 44100, 16, &[0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x00]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        let frames: Vec<_> = wave_reader.frames().map(|frame| frame.unwrap()).collect();
+        assert_eq!(vec![vec![1, 2], vec![3, 4]], frames);
+    }
+
+    // RF64/BW64 tests. The "data" chunk's 32-bit size is the 0xffffffff placeholder, and its
+    // real size is carried by the "ds64" chunk instead.
+
+    fn rf64_header(num_channels: u16, sample_rate: u32, bits_per_sample: u16, data: &[u8]) -> Vec<u8> {
+        let block_align = num_channels * ((bits_per_sample + 7) / 8);
+        let byte_rate = sample_rate * block_align as u32;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RF64");
+        bytes.extend_from_slice(&RF64_SIZE_PLACEHOLDER.to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"ds64");
+        bytes.extend_from_slice(&28u32.to_le_bytes()); // chunk size: no extra table entries.
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // riffSize, unused by the reader.
+        bytes.extend_from_slice(&(data.len() as u64).to_le_bytes()); // dataSize.
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // sampleCount, unused by the reader.
+        bytes.extend_from_slice(&0u32.to_le_bytes()); // table length.
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_UNCOMPRESSED_PCM.to_le_bytes());
+        bytes.extend_from_slice(&num_channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&byte_rate.to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&bits_per_sample.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&RF64_SIZE_PLACEHOLDER.to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_wave_reader_new_reads_rf64_file() {
+        let bytes = rf64_header(1, 44100, 16, &[0x01, 0x02, 0x03, 0x04]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(1, wave_reader.pcm_format.num_channels);
+        assert_eq!(0x0201, wave_reader.read_sample().unwrap());
+        assert_eq!(0x0403, wave_reader.read_sample().unwrap());
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::NotEnoughData)),
+                        wave_reader.read_sample());
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_rf64_missing_ds64_chunk() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RF64");
+        bytes.extend_from_slice(&RF64_SIZE_PLACEHOLDER.to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::MissingDs64Chunk)),
+                        WaveReader::new(Cursor::new(bytes)));
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_rf64_ds64_table_length_overflows_chunk_size() {
+        // A 28-byte "ds64" chunk (no room for any table entries) that lies about having
+        // almost 2^32 of them; the table-length check must reject this before any table
+        // entries are read.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RF64");
+        bytes.extend_from_slice(&RF64_SIZE_PLACEHOLDER.to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"ds64");
+        bytes.extend_from_slice(&28u32.to_le_bytes()); // chunk size: no room for table entries.
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // riffSize
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // dataSize
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // sampleCount
+        bytes.extend_from_slice(&0xffff_fff0u32.to_le_bytes()); // table length, way too large.
+
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::MissingDs64Chunk)),
+                        WaveReader::new(Cursor::new(bytes)));
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_rf64_ds64_table_truncated() {
+        // `chunk_size` is internally consistent with a huge `table_length`, but `chunk_size`
+        // is itself just another untrusted value: the file doesn't actually contain anywhere
+        // near that much data. This must fail cleanly (an IO/EOF error) instead of
+        // pre-allocating a `Vec` sized from the declared table length.
+        let table_length: u32 = 0x0fff_ffff;
+        let chunk_size = 28 + table_length * 12;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RF64");
+        bytes.extend_from_slice(&RF64_SIZE_PLACEHOLDER.to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"ds64");
+        bytes.extend_from_slice(&chunk_size.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // riffSize
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // dataSize
+        bytes.extend_from_slice(&0u64.to_le_bytes()); // sampleCount
+        bytes.extend_from_slice(&table_length.to_le_bytes());
+        // No table entries actually follow; the stream just ends here.
+
+        assert_matches!(Err(ReadError::Io(_)), WaveReader::new(Cursor::new(bytes)));
+    }
+
+    // Microsoft ADPCM tests.
+
+    fn adpcm_header(num_channels: u16,
+                    sample_rate: u32,
+                    block_align: u16,
+                    samples_per_block: u16,
+                    coefficients: &[(i16, i16)],
+                    data: &[u8])
+                    -> Vec<u8> {
+        let byte_rate = sample_rate * block_align as u32;
+        let coef_table_size = (coefficients.len() as u32) * 4;
+        let cb_size = 4 + coef_table_size; // wSamplesPerBlock + wNumCoef + the coefficient table.
+        let fmt_size = 16 + 2 + cb_size; // canonical fmt fields + cbSize field + the extension.
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]); // RIFF chunk size, unused by the reader.
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&fmt_size.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_MS_ADPCM.to_le_bytes());
+        bytes.extend_from_slice(&num_channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&byte_rate.to_le_bytes());
+        bytes.extend_from_slice(&block_align.to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes()); // bits_per_sample
+        bytes.extend_from_slice(&(cb_size as u16).to_le_bytes());
+        bytes.extend_from_slice(&samples_per_block.to_le_bytes());
+        bytes.extend_from_slice(&(coefficients.len() as u16).to_le_bytes());
+        for &(coef1, coef2) in coefficients {
+            bytes.extend_from_slice(&coef1.to_le_bytes());
+            bytes.extend_from_slice(&coef2.to_le_bytes());
+        }
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_wave_reader_new_reads_adpcm_fmt_chunk() {
+        // One coefficient pair: coef1 = 256, coef2 = 0.
+        let bytes = adpcm_header(1, 22050, 8, 4, &[(256, 0)], &[]);
+        let wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(1, wave_reader.pcm_format.num_channels);
+        assert_eq!(22050, wave_reader.pcm_format.sample_rate);
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_adpcm_zero_channels() {
+        // Without the num_channels validation in WaveReader::new, decode_adpcm_block's
+        // `nibble_bytes.len() * 2 / num_channels` would panic with "attempt to divide by zero".
+        let bytes = adpcm_header(0, 22050, 8, 4, &[(256, 0)], &[]);
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::InvalidChannelCount)),
+                        WaveReader::new(Cursor::new(bytes)));
+    }
+
+    #[test]
+    fn test_read_sample_decodes_mono_adpcm_block() {
+        // coef1 = 256, coef2 = 0 makes the predictor just echo the previous sample, which
+        // keeps the arithmetic easy to verify by hand.
+        //
+        // Block preamble: predictor = 0, delta = 16, sample1 = 0, sample2 = 0. The first two
+        // decoded samples are sample2 then sample1, i.e. 0 and 0.
+        //
+        // Nibble 1 (high nibble of 0x11): error = 1, predicted = 0, new_sample = 0 + 1*16 = 16.
+        // Nibble 2 (low nibble of 0x11): error = 1, predicted = sample1*256>>8 = 16,
+        // new_sample = 16 + 1*16 = 32.
+        let mut preamble = Vec::new();
+        preamble.push(0u8); // predictor index
+        preamble.extend_from_slice(&16i16.to_le_bytes()); // delta
+        preamble.extend_from_slice(&0i16.to_le_bytes()); // sample1
+        preamble.extend_from_slice(&0i16.to_le_bytes()); // sample2
+        preamble.push(0x11); // two nibbles: 1, 1
+
+        let bytes = adpcm_header(1, 22050, preamble.len() as u16, 4, &[(256, 0)], &preamble);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(0, wave_reader.read_sample().unwrap());
+        assert_eq!(0, wave_reader.read_sample().unwrap());
+        assert_eq!(16, wave_reader.read_sample().unwrap());
+        assert_eq!(32, wave_reader.read_sample().unwrap());
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::NotEnoughData)),
+                        wave_reader.read_sample());
+    }
+
+    #[test]
+    fn test_read_sample_err_adpcm_block_arithmetic_overflow() {
+        // coef1 = coef2 = sample1 = sample2 = i16::min_value() makes
+        // `sample1*coef1 + sample2*coef2` overflow i32; this must be reported as a malformed
+        // block instead of panicking.
+        let mut preamble = Vec::new();
+        preamble.push(0u8); // predictor index
+        preamble.extend_from_slice(&16i16.to_le_bytes()); // delta
+        preamble.extend_from_slice(&(-32768i16).to_le_bytes()); // sample1
+        preamble.extend_from_slice(&(-32768i16).to_le_bytes()); // sample2
+        preamble.push(0x11); // two nibbles: 1, 1
+
+        let bytes = adpcm_header(1, 22050, preamble.len() as u16, 4, &[(-32768, -32768)], &preamble);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::InvalidAdpcmBlock)),
+                        wave_reader.read_sample());
+    }
+
+    #[test]
+    fn test_read_sample_adpcm_samples_per_block_bounded_by_available_nibble_data() {
+        // `samples_per_block` claims far more samples than the one-byte (two-nibble) block
+        // actually holds; this must not pre-allocate based on the declared count.
+        let mut preamble = Vec::new();
+        preamble.push(0u8);
+        preamble.extend_from_slice(&16i16.to_le_bytes());
+        preamble.extend_from_slice(&0i16.to_le_bytes());
+        preamble.extend_from_slice(&0i16.to_le_bytes());
+        preamble.push(0x11);
+
+        let bytes = adpcm_header(1, 22050, preamble.len() as u16, u16::max_value(), &[(256, 0)], &preamble);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(0, wave_reader.read_sample().unwrap());
+        assert_eq!(0, wave_reader.read_sample().unwrap());
+        assert_eq!(16, wave_reader.read_sample().unwrap());
+        assert_eq!(32, wave_reader.read_sample().unwrap());
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::NotEnoughData)),
+                        wave_reader.read_sample());
+    }
+
+    #[test]
+    fn test_read_sample_err_adpcm_block_references_unknown_predictor() {
+        let mut preamble = Vec::new();
+        preamble.push(5u8); // predictor index, but the coefficient table only has 1 entry
+        preamble.extend_from_slice(&16i16.to_le_bytes());
+        preamble.extend_from_slice(&0i16.to_le_bytes());
+        preamble.extend_from_slice(&0i16.to_le_bytes());
+        preamble.push(0x11);
+
+        let bytes = adpcm_header(1, 22050, preamble.len() as u16, 4, &[(256, 0)], &preamble);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::InvalidAdpcmBlock)),
+                        wave_reader.read_sample());
+    }
+
+    #[test]
+    fn test_wave_reader_new_err_adpcm_fmt_extension_too_short() {
+        // "cbSize" claims less than the 4 bytes an ADPCM extension needs.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&19u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_MS_ADPCM.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&22050u32.to_le_bytes());
+        bytes.extend_from_slice(&22050u32.to_le_bytes());
+        bytes.extend_from_slice(&8u16.to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes()); // cbSize, too small.
+
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::FmtChunkTooShort)),
+                        WaveReader::new(Cursor::new(bytes)));
+    }
+
+    // Chunk enumeration and Broadcast Wave metadata tests.
+
+    fn padded(value: &[u8], len: usize) -> Vec<u8> {
+        let mut bytes = value.to_vec();
+        bytes.resize(len, 0);
+        bytes
+    }
+
+    fn bext_chunk_payload(description: &[u8],
+                          originator: &[u8],
+                          originator_reference: &[u8],
+                          origination_date: &[u8],
+                          origination_time: &[u8],
+                          time_reference: u64,
+                          coding_history: &[u8])
+                          -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&padded(description, 256));
+        bytes.extend_from_slice(&padded(originator, 32));
+        bytes.extend_from_slice(&padded(originator_reference, 32));
+        bytes.extend_from_slice(&padded(origination_date, 10));
+        bytes.extend_from_slice(&padded(origination_time, 8));
+        bytes.extend_from_slice(&((time_reference & 0xffff_ffff) as u32).to_le_bytes());
+        bytes.extend_from_slice(&((time_reference >> 32) as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 256]); // version + UMID + loudness fields + reserved.
+        bytes.extend_from_slice(coding_history);
+        bytes
+    }
+
+    fn wave_file_with_bext_chunk(bext_payload: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"bext");
+        bytes.extend_from_slice(&(bext_payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(bext_payload);
+        if bext_payload.len() & 1 == 1 {
+            bytes.push(0);
+        }
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_UNCOMPRESSED_PCM.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&44100u32.to_le_bytes());
+        bytes.extend_from_slice(&88200u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_wave_reader_chunks_enumerates_subchunks() {
+        let bytes = canonical_header(1, 44100, 16, &[0x01, 0x02]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        let tags: Vec<[u8; 4]> =
+            wave_reader.chunks().unwrap().map(|chunk| chunk.unwrap().tag).collect();
+        assert_eq!(vec![*b"fmt ", *b"data"], tags);
+    }
+
+    #[test]
+    fn test_wave_reader_read_bext_chunk() {
+        let payload = bext_chunk_payload(b"test description",
+                                          b"test originator",
+                                          b"test reference",
+                                          b"2026-07-30",
+                                          b"12-00-00",
+                                          12345,
+                                          b"A=PCM,F=44100");
+        let bytes = wave_file_with_bext_chunk(&payload, &[0x01, 0x00]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+
+        let bext = wave_reader.read_bext_chunk().unwrap().unwrap();
+        assert_eq!(b"test description", &bext.description[..16]);
+        assert_eq!(b"test originator", &bext.originator[..15]);
+        assert_eq!(12345, bext.time_reference);
+        assert_eq!(b"A=PCM,F=44100".to_vec(), bext.coding_history);
+
+        // Reading the "bext" chunk doesn't disturb the reader's position for sample reading.
+        assert_eq!(1, wave_reader.read_sample().unwrap());
+    }
+
+    #[test]
+    fn test_wave_reader_read_bext_chunk_err_declared_size_exceeds_available_data() {
+        // The "bext" chunk claims a multi-gigabyte "CodingHistory", but the file actually ends
+        // right after the fixed-length fields. This must not pre-allocate a multi-gigabyte
+        // buffer, and must fail cleanly once the declared size can't be satisfied.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"bext");
+        let huge_size = BEXT_FIXED_SIZE as u32 + 0x7fff_ffff;
+        bytes.extend_from_slice(&huge_size.to_le_bytes());
+        bytes.extend_from_slice(&[0u8; BEXT_FIXED_SIZE as usize]);
+
+        let mut reader = Cursor::new(bytes);
+        reader.set_position(20); // Just past the "bext" tag and size.
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::BextChunkTooShort)),
+                        read_bext_metadata(&mut reader, huge_size as u64));
+    }
+
+    #[test]
+    fn test_wave_reader_read_bext_chunk_none_when_absent() {
+        let bytes = canonical_header(1, 44100, 16, &[]);
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_matches!(Ok(None), wave_reader.read_bext_chunk());
+    }
+
+    #[test]
+    fn test_wave_reader_read_bext_chunk_err_too_short() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"bext");
+        bytes.extend_from_slice(&10u32.to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 10]);
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_UNCOMPRESSED_PCM.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&44100u32.to_le_bytes());
+        bytes.extend_from_slice(&88200u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+
+        let mut wave_reader = WaveReader::new(Cursor::new(bytes)).unwrap();
+        assert_matches!(Err(ReadError::Format(FormatErrorKind::BextChunkTooShort)),
+                        wave_reader.read_bext_chunk());
+    }
+
+    // WaveWriter tests.
+
+    #[test]
+    fn test_wave_writer_round_trips_through_wave_reader() {
+        let mut wave_writer = WaveWriter::new(Cursor::new(Vec::new()), 2, 44100, 16).unwrap();
+        wave_writer.write_sample(1).unwrap();
+        wave_writer.write_sample(2).unwrap();
+        wave_writer.write_sample(-1).unwrap();
+        wave_writer.write_sample(-2).unwrap();
+        let mut cursor = wave_writer.finalize().unwrap();
+        cursor.set_position(0);
+
+        let mut wave_reader = WaveReader::new(cursor).unwrap();
+        assert_eq!(2, wave_reader.pcm_format.num_channels);
+        assert_eq!(44100, wave_reader.pcm_format.sample_rate);
+        assert_eq!(16, wave_reader.pcm_format.bits_per_sample);
+
+        let frames: Vec<_> = wave_reader.frames().map(|frame| frame.unwrap()).collect();
+        assert_eq!(vec![vec![1, 2], vec![-1, -2]], frames);
+    }
+
+    #[test]
+    fn test_wave_writer_pads_odd_length_data_chunk() {
+        let mut wave_writer = WaveWriter::new(Cursor::new(Vec::new()), 1, 44100, 8).unwrap();
+        wave_writer.write_sample(1).unwrap();
+        let cursor = wave_writer.finalize().unwrap();
+        // "RIFF"+size+"WAVE"+"fmt "+size+16 bytes fmt+"data"+size+1 sample+1 pad byte.
+        assert_eq!(44 + 2, cursor.into_inner().len());
+    }
+
+    #[test]
+    fn test_wave_writer_write_sample_err_out_of_range() {
+        let mut wave_writer = WaveWriter::new(Cursor::new(Vec::new()), 1, 44100, 8).unwrap();
+        assert_matches!(Err(WriteError::SampleOutOfRange(256)), wave_writer.write_sample(256));
+    }
+
+    #[test]
+    fn test_wave_writer_new_err_unsupported_bits_per_sample() {
+        assert_matches!(Err(WriteError::UnsupportedBitsPerSample(12)),
+                        WaveWriter::new(Cursor::new(Vec::new()), 1, 44100, 12));
+    }
+
+    #[test]
+    fn test_wave_writer_new_err_too_many_channels_for_bits_per_sample() {
+        // 16384 * 4 == 65536, which would overflow the u16 "block align" field.
+        assert_matches!(Err(WriteError::TooManyChannelsForBitsPerSample(16384)),
+                        WaveWriter::new(Cursor::new(Vec::new()), 16384, 44100, 32));
+    }
+
+    #[test]
+    fn test_wave_writer_new_accepts_block_align_at_u16_boundary() {
+        // 16383 * 4 == 65532, which still fits in a u16.
+        assert_matches!(Ok(_), WaveWriter::new(Cursor::new(Vec::new()), 16383, 44100, 32));
+    }
+
+    #[test]
+    fn test_wave_writer_new_err_sample_rate_too_high_for_block_align() {
+        // block_align == 65532 (16383 channels at 32-bit), and 1_000_000 * 65532 overflows u32.
+        assert_matches!(Err(WriteError::SampleRateTooHighForBlockAlign(1_000_000)),
+                        WaveWriter::new(Cursor::new(Vec::new()), 16383, 1_000_000, 32));
+    }
 }